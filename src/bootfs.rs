@@ -0,0 +1,118 @@
+// Copyright (c) 2021 Miguel Peláez
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Embeds extra files into the FAT/ESP image produced by the bootloader
+//! builder, `bootfs`-manifest style (a `source=destination` pair per line).
+
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use fatfs::{FileSystem, FsOptions};
+
+use crate::error::BootFsError;
+
+/// A single `source=destination` mapping parsed out of a `--bootfs` manifest.
+#[derive(Debug, Clone)]
+pub struct BootFsEntry {
+    pub source: PathBuf,
+    pub destination: String,
+}
+
+/// Parses a bootfs manifest: one `source=destination` pair per non-empty,
+/// non-comment (`#`) line. Relative sources are resolved against the
+/// manifest's own directory.
+pub fn parse_manifest(manifest: &Path) -> Result<Vec<BootFsEntry>, BootFsError> {
+    let contents = std::fs::read_to_string(manifest).map_err(BootFsError::ReadManifest)?;
+    let manifest_dir = manifest.parent().unwrap_or_else(|| Path::new("."));
+
+    let mut entries = Vec::new();
+    let mut destinations = HashSet::new();
+
+    for line in contents.lines() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (source, destination) = line
+            .split_once('=')
+            .ok_or_else(|| BootFsError::InvalidEntry(line.to_owned()))?;
+
+        let destination = normalize_destination(destination);
+
+        if !destinations.insert(destination.clone()) {
+            return Err(BootFsError::DuplicateDestination(destination));
+        }
+
+        entries.push(BootFsEntry {
+            source: manifest_dir.join(source.trim()),
+            destination,
+        });
+    }
+
+    Ok(entries)
+}
+
+fn normalize_destination(destination: &str) -> String {
+    destination.trim().trim_start_matches('/').replace('\\', "/")
+}
+
+/// Copies every declared bootfs entry into the FAT image at `image_path`,
+/// creating intermediate directories as needed, then optionally writes the
+/// assembled kernel command line to `cmdline.txt` at the root of the image.
+pub fn embed(image_path: &Path, entries: &[BootFsEntry], cmdline: Option<&str>) -> Result<(), BootFsError> {
+    let image = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(image_path)
+        .map_err(BootFsError::OpenImage)?;
+
+    let fs = FileSystem::new(image, FsOptions::new()).map_err(BootFsError::OpenFat)?;
+
+    for entry in entries {
+        let mut components: Vec<&str> = entry.destination.split('/').filter(|c| !c.is_empty()).collect();
+        let file_name = components
+            .pop()
+            .ok_or_else(|| BootFsError::InvalidEntry(entry.destination.clone()))?;
+
+        let mut dir = fs.root_dir();
+        for component in components {
+            dir = match dir.open_dir(component) {
+                Ok(existing) => existing,
+                Err(fatfs::Error::NotFound) => dir.create_dir(component).map_err(BootFsError::CreateDir)?,
+                Err(err) => return Err(BootFsError::CreateDir(err)),
+            };
+        }
+
+        let mut file = dir.create_file(file_name).map_err(BootFsError::CreateFile)?;
+        let mut source = File::open(&entry.source).map_err(BootFsError::ReadSource)?;
+        std::io::copy(&mut source, &mut file).map_err(BootFsError::WriteFile)?;
+    }
+
+    if let Some(cmdline) = cmdline {
+        let mut file = fs.root_dir().create_file("cmdline.txt").map_err(BootFsError::CreateFile)?;
+        file.write_all(cmdline.as_bytes()).map_err(BootFsError::WriteFile)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,142 @@
+// Copyright (c) 2021 Miguel Peláez
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[error("unknown architecture `{0}`, expected one of: x86_64, aarch64, riscv64")]
+pub struct UnknownArch(pub String);
+
+#[derive(Error, Debug)]
+pub enum BootImageError {
+    #[error("output directory does not exist, pass --create-out to create it")]
+    OutNotExist,
+
+    #[error("failed to build kernel crate")]
+    BuildFailed,
+
+    #[error("kernel Cargo.toml is missing a [package] section")]
+    KernelManifest,
+
+    #[error("could not determine kernel crate root")]
+    KernelRootNotFound,
+
+    #[error("--bootfs/--boot-arg requires a UEFI image, but none was built")]
+    BootFsRequiresUefi,
+
+    #[error("--bootfs/--boot-arg can't be combined with --pxe, which produces a TFTP directory, not a uefi image")]
+    BootFsIncompatibleWithPxe,
+
+    #[error("--integrity requires a bios or uefi disk image, but none was built")]
+    IntegrityRequiresImage,
+
+    #[error("failed to create disk image")]
+    CreateDiskImage(#[from] CreateDiskImageError),
+
+    #[error("failed to embed bootfs manifest")]
+    BootFs(#[from] BootFsError),
+
+    #[error("failed to build resource image")]
+    Verity(#[from] VerityError),
+
+    #[error(transparent)]
+    LocateManifest(#[from] locate_cargo_manifest::LocateManifestError),
+
+    #[error(transparent)]
+    Manifest(#[from] cargo_manifest::Error),
+
+    #[error(transparent)]
+    Logger(#[from] log::SetLoggerError),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum CreateDiskImageError {
+    #[error("could not locate the `bootloader` crate")]
+    RootNotFound,
+
+    #[error("failed to build the disk image")]
+    BuildFailed,
+
+    #[error("failed to move generated image into the output directory")]
+    Move(#[source] std::io::Error),
+
+    #[error("failed to locate the moved image")]
+    FindMoved(#[source] std::io::Error),
+
+    #[error("failed to assemble PXE/TFTP boot directory")]
+    Pxe(#[source] std::io::Error),
+
+    #[error("failed to read EFI bootloader from uefi image")]
+    OpenFat(#[source] fatfs::Error<std::io::Error>),
+
+    #[error("failed to read EFI bootloader directory from uefi image")]
+    PxeFat(#[source] fatfs::Error<std::io::Error>),
+
+    #[error(transparent)]
+    LocateBootloader(#[from] bootloader_locator::LocateBootloaderError),
+
+    #[error(transparent)]
+    LocateManifest(#[from] locate_cargo_manifest::LocateManifestError),
+}
+
+#[derive(Error, Debug)]
+pub enum BootFsError {
+    #[error("failed to read bootfs manifest")]
+    ReadManifest(#[source] std::io::Error),
+
+    #[error("invalid bootfs manifest entry `{0}`, expected `source=destination`")]
+    InvalidEntry(String),
+
+    #[error("duplicate bootfs destination `{0}`")]
+    DuplicateDestination(String),
+
+    #[error("failed to open disk image")]
+    OpenImage(#[source] std::io::Error),
+
+    #[error("failed to open FAT filesystem")]
+    OpenFat(#[source] fatfs::Error<std::io::Error>),
+
+    #[error("failed to create directory in FAT filesystem")]
+    CreateDir(#[source] fatfs::Error<std::io::Error>),
+
+    #[error("failed to create file in FAT filesystem")]
+    CreateFile(#[source] fatfs::Error<std::io::Error>),
+
+    #[error("failed to read bootfs source file")]
+    ReadSource(#[source] std::io::Error),
+
+    #[error("failed to write file into FAT filesystem")]
+    WriteFile(#[source] std::io::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum VerityError {
+    #[error("failed to read disk image")]
+    ReadImage(#[source] std::io::Error),
+
+    #[error("failed to compress image payload")]
+    Compress(#[source] std::io::Error),
+
+    #[error("failed to write resource image")]
+    WriteResource(#[source] std::io::Error),
+}
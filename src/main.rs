@@ -20,19 +20,20 @@
 
 use std::path::{Path, PathBuf};
 use std::process::{exit, Command};
-use std::time::Duration;
 
 use cargo_manifest::Manifest;
 use clap::Clap;
 use log::{debug, error, info};
 use simple_logger::SimpleLogger;
-use wait_timeout::ChildExt;
 
 use crate::error::*;
 use crate::opts::*;
 
+mod bootfs;
 mod error;
 mod opts;
+mod qemu;
+mod verity;
 
 fn main() -> Result<(), BootImageError> {
     SimpleLogger::new().init()?;
@@ -42,10 +43,30 @@ fn main() -> Result<(), BootImageError> {
     match opts.subcmd {
         SubCommands::Run(opts) => {
             let binary_path = opts.binary_path.canonicalize()?;
-            let diskimage = create_kernel_diskimage(&binary_path, false, true, opts.out)?
-                .0
-                .expect("Booteable image not found");
-            run_vm(diskimage, opts.run_args, opts.timeout);
+            // aarch64/riscv64 only ever boot through UEFI+virt firmware, never a raw BIOS drive.
+            let needs_uefi = opts.pxe || opts.arch != Arch::X86_64;
+            let diskimage = create_kernel_diskimage(&binary_path, !needs_uefi, needs_uefi, opts.pxe, opts.arch, opts.out)?;
+
+            let vm_config = qemu::VmConfig {
+                arch: opts.arch,
+                run_args: opts.run_args,
+                timeout: opts.timeout,
+                gdb: opts.gdb,
+                serial: opts.serial,
+                no_reboot: opts.no_reboot,
+                success_exit_code: opts.success_exit_code,
+            };
+
+            if opts.pxe {
+                let pxe_dir = diskimage.2.expect("PXE boot directory not found");
+                qemu::run_pxe(pxe_dir, vm_config);
+            } else if needs_uefi {
+                let diskimage = diskimage.1.expect("Booteable image not found");
+                qemu::run(diskimage, vm_config);
+            } else {
+                let diskimage = diskimage.0.expect("Booteable image not found");
+                qemu::run(diskimage, vm_config);
+            }
         },
         SubCommands::Build(opts) => {
             if let Err(err) = build(opts) {
@@ -59,6 +80,10 @@ fn main() -> Result<(), BootImageError> {
 }
 
 fn build(opts: BuildOpts) -> Result<(), BootImageError> {
+    if opts.pxe && (opts.bootfs.is_some() || !opts.boot_args.is_empty()) {
+        return Err(BootImageError::BootFsIncompatibleWithPxe);
+    }
+
     if !opts.out.exists() {
         if !opts.create_out {
             return Err(BootImageError::OutNotExist);
@@ -71,6 +96,7 @@ fn build(opts: BuildOpts) -> Result<(), BootImageError> {
 
     let mut build_cmd = Command::new(env!("CARGO"));
     build_cmd.args(opts.build_cmd.split(" "));
+    build_cmd.arg("--target").arg(opts.arch.target_triple());
 
     if !build_cmd.status()?.success() {
         return Err(BootImageError::BuildFailed);
@@ -87,7 +113,7 @@ fn build(opts: BuildOpts) -> Result<(), BootImageError> {
 
     info!("Creating disk image");
 
-    let target_dir = target_dir_root.join(&opts.target).join("release");
+    let target_dir = target_dir_root.join(opts.arch.target_triple()).join("release");
     let kernel_name = package.name;
     let binary_path = target_dir.join(format!("{}.elf", &kernel_name));
 
@@ -97,6 +123,8 @@ fn build(opts: BuildOpts) -> Result<(), BootImageError> {
         &binary_path.canonicalize()?,
         !opts.disable_bios,
         !opts.disable_uefi,
+        opts.pxe,
+        opts.arch,
         opts.out,
     )?;
 
@@ -116,48 +144,93 @@ fn build(opts: BuildOpts) -> Result<(), BootImageError> {
         );
     }
 
+    if let Some(pxe_dir) = &diskimage.2 {
+        info!(
+            "Assembled PXE boot directory for {} at {}",
+            kernel_name,
+            pxe_dir.display()
+        );
+    }
+
+    if opts.bootfs.is_some() || !opts.boot_args.is_empty() {
+        let uefi_image = diskimage.1.as_ref().ok_or(BootImageError::BootFsRequiresUefi)?;
+
+        let entries = match &opts.bootfs {
+            Some(manifest) => bootfs::parse_manifest(manifest)?,
+            None => Vec::new(),
+        };
+
+        let cmdline = if opts.boot_args.is_empty() {
+            None
+        } else {
+            Some(opts.boot_args.join(" "))
+        };
+
+        info!("Embedding bootfs files into uefi image");
+        bootfs::embed(uefi_image, &entries, cmdline.as_deref())?;
+    }
+
+    if opts.integrity {
+        let image = diskimage
+            .0
+            .as_ref()
+            .or(diskimage.1.as_ref())
+            .ok_or(BootImageError::IntegrityRequiresImage)?;
+        let resource_path = verity::resource_image_path(image);
+
+        info!("Computing resource image integrity hash");
+        verity::build_resource_image(image, &resource_path, opts.compress)?;
+        info!("Wrote resource image to {}", resource_path.display());
+    }
+
     Ok(())
 }
 
-fn run_vm(diskimage: PathBuf, args: String, timeout: Option<u64>) {
-    let mut child = Command::new("qemu-system-x86_64")
-        .arg("-drive")
-        .arg(format!("format=raw,file={}", diskimage.display()))
-        .args(args.split(&[' ', '|'][..]))
-        .spawn()
-        .expect("Failed to start virtual machine");
-
-    let status_code = if let Some(timeout) = timeout {
-        let timeout = Duration::from_secs(timeout);
-
-        match child
-            .wait_timeout(timeout)
-            .expect("Failed to wait for virtual machine")
-        {
-            Some(status) => status.code(),
-            None => {
-                // child hasn't exited yet
-                child.kill().unwrap();
-                child.wait().unwrap().code()
-            },
-        }
-    } else {
-        child.wait().expect("Failed to wait for virtual machine").code()
-    };
-
-    exit(
-        status_code
-            .map(|exit| if exit == 5 { 0 } else { exit })
-            .unwrap_or(1),
-    );
+/// `bootloader` crate to locate for a given architecture: each target gets
+/// its own loader crate rather than one crate supporting every arch.
+fn bootloader_crate_name(arch: Arch) -> &'static str {
+    match arch {
+        Arch::X86_64 => "bootloader",
+        Arch::Aarch64 => "bootloader-aarch64",
+        Arch::Riscv64 => "bootloader-riscv64",
+    }
 }
 
-fn create_kernel_diskimage(
-    kernel_binary_path: &Path, uefi: bool, _bios: bool, out: PathBuf,
-) -> Result<(Option<PathBuf>, Option<PathBuf>), CreateDiskImageError> {
-    let bootloader_manifest_path = bootloader_locator::locate_bootloader("bootloader")?;
-    let kernel_manifest_path = locate_cargo_manifest::locate_manifest()?;
+/// Extracts the EFI bootloader out of the ESP image and lays it out next to
+/// the kernel binary in a directory servable over TFTP.
+fn assemble_pxe_directory(
+    uefi_image: &Path, kernel_binary_path: &Path, arch: Arch, out: &Path,
+) -> Result<PathBuf, CreateDiskImageError> {
+    let pxe_dir = out.join("pxe");
+    std::fs::create_dir_all(&pxe_dir).map_err(CreateDiskImageError::Pxe)?;
+
+    let image = std::fs::File::open(uefi_image).map_err(CreateDiskImageError::Pxe)?;
+    let fs = fatfs::FileSystem::new(image, fatfs::FsOptions::new()).map_err(CreateDiskImageError::OpenFat)?;
+
+    let boot_dir = fs
+        .root_dir()
+        .open_dir("EFI")
+        .and_then(|efi| efi.open_dir("BOOT"))
+        .map_err(CreateDiskImageError::PxeFat)?;
+
+    let efi_file_name = arch.efi_boot_file_name();
+    let mut efi_file = boot_dir.open_file(efi_file_name).map_err(CreateDiskImageError::PxeFat)?;
+    let mut out_efi = std::fs::File::create(pxe_dir.join(efi_file_name)).map_err(CreateDiskImageError::Pxe)?;
+    std::io::copy(&mut efi_file, &mut out_efi).map_err(CreateDiskImageError::Pxe)?;
+
+    let kernel_name = kernel_binary_path
+        .file_name()
+        .ok_or(CreateDiskImageError::RootNotFound)?;
+    std::fs::copy(kernel_binary_path, pxe_dir.join(kernel_name)).map_err(CreateDiskImageError::Pxe)?;
 
+    Ok(pxe_dir)
+}
+
+/// Invokes the `bootloader` crate's builder once for a single firmware mode,
+/// matching its own independent `bios`/`uefi` feature gates.
+fn build_firmware_image(
+    bootloader_manifest_path: &Path, kernel_manifest_path: &Path, kernel_binary_path: &Path, firmware: &str,
+) -> Result<(), CreateDiskImageError> {
     let mut build_cmd = Command::new(env!("CARGO"));
     build_cmd.current_dir(
         bootloader_manifest_path
@@ -166,8 +239,8 @@ fn create_kernel_diskimage(
     );
     build_cmd.arg("builder");
     build_cmd.arg("--quiet");
-    build_cmd.arg("--kernel-manifest").arg(&kernel_manifest_path);
-    build_cmd.arg("--kernel-binary").arg(&kernel_binary_path);
+    build_cmd.arg("--kernel-manifest").arg(kernel_manifest_path);
+    build_cmd.arg("--kernel-binary").arg(kernel_binary_path);
     build_cmd.arg("--target-dir").arg(
         kernel_manifest_path
             .parent()
@@ -177,10 +250,7 @@ fn create_kernel_diskimage(
     build_cmd
         .arg("--out-dir")
         .arg(kernel_binary_path.parent().unwrap());
-
-    if !uefi {
-        build_cmd.arg("--firmware").arg("bios");
-    }
+    build_cmd.arg("--firmware").arg(firmware);
 
     if !build_cmd
         .status()
@@ -189,6 +259,28 @@ fn create_kernel_diskimage(
     {
         return Err(CreateDiskImageError::BuildFailed);
     }
+
+    Ok(())
+}
+
+fn create_kernel_diskimage(
+    kernel_binary_path: &Path, bios: bool, uefi: bool, pxe: bool, arch: Arch, out: PathBuf,
+) -> Result<(Option<PathBuf>, Option<PathBuf>, Option<PathBuf>), CreateDiskImageError> {
+    let bootloader_manifest_path = bootloader_locator::locate_bootloader(bootloader_crate_name(arch))?;
+    let kernel_manifest_path = locate_cargo_manifest::locate_manifest()?;
+
+    // pxe only ever needs a UEFI image to extract the EFI bootloader from.
+    let build_bios = bios && !pxe;
+    let build_uefi = uefi || pxe;
+
+    if build_bios {
+        build_firmware_image(&bootloader_manifest_path, &kernel_manifest_path, kernel_binary_path, "bios")?;
+    }
+
+    if build_uefi {
+        build_firmware_image(&bootloader_manifest_path, &kernel_manifest_path, kernel_binary_path, "uefi")?;
+    }
+
     info!("Created images. Copying to output directory");
 
     let kernel_binary_name = kernel_binary_path
@@ -207,7 +299,14 @@ fn create_kernel_diskimage(
         .ok_or(CreateDiskImageError::RootNotFound)?
         .join(format!("bootimage-uefi-{}.img", kernel_binary_name));
 
-    let bios = if biosimage.exists() {
+    let pxedir = if pxe {
+        info!("Assembling PXE boot directory");
+        Some(assemble_pxe_directory(&uefiimage, kernel_binary_path, arch, &out)?)
+    } else {
+        None
+    };
+
+    let bios_out = if build_bios {
         let out = &out.join("bios.img");
         std::fs::rename(&biosimage, out).map_err(CreateDiskImageError::Move)?;
         Some(out.canonicalize().map_err(CreateDiskImageError::FindMoved)?)
@@ -215,7 +314,7 @@ fn create_kernel_diskimage(
         None
     };
 
-    let uefi = if uefiimage.exists() {
+    let uefi_out = if build_uefi && !pxe {
         let out = &out.join("uefi.img");
         std::fs::rename(&uefiimage, out).map_err(CreateDiskImageError::Move)?;
         Some(out.canonicalize().map_err(CreateDiskImageError::FindMoved)?)
@@ -223,5 +322,5 @@ fn create_kernel_diskimage(
         None
     };
 
-    Ok((bios, uefi))
+    Ok((bios_out, uefi_out, pxedir))
 }
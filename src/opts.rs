@@ -0,0 +1,182 @@
+// Copyright (c) 2021 Miguel Peláez
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use clap::Clap;
+
+use crate::error::UnknownArch;
+
+/// Target architecture to build and/or boot the kernel for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    X86_64,
+    Aarch64,
+    Riscv64,
+}
+
+impl Arch {
+    /// Rust target triple used to compile the kernel crate for this architecture.
+    pub fn target_triple(&self) -> &'static str {
+        match self {
+            Arch::X86_64 => "x86_64-unknown-none",
+            Arch::Aarch64 => "aarch64-unknown-none",
+            Arch::Riscv64 => "riscv64gc-unknown-none-elf",
+        }
+    }
+
+    /// QEMU system binary able to emulate this architecture.
+    pub fn qemu_binary(&self) -> &'static str {
+        match self {
+            Arch::X86_64 => "qemu-system-x86_64",
+            Arch::Aarch64 => "qemu-system-aarch64",
+            Arch::Riscv64 => "qemu-system-riscv64",
+        }
+    }
+
+    /// Name of the EFI bootloader file the `bootloader` crate places under
+    /// `/EFI/BOOT/` in the ESP, which is architecture-specific per the UEFI spec.
+    pub fn efi_boot_file_name(&self) -> &'static str {
+        match self {
+            Arch::X86_64 => "BOOTX64.EFI",
+            Arch::Aarch64 => "BOOTAA64.EFI",
+            Arch::Riscv64 => "BOOTRISCV64.EFI",
+        }
+    }
+}
+
+impl FromStr for Arch {
+    type Err = UnknownArch;
+
+    fn from_str(arch: &str) -> Result<Self, Self::Err> {
+        match arch {
+            "x86_64" => Ok(Arch::X86_64),
+            "aarch64" => Ok(Arch::Aarch64),
+            "riscv64" => Ok(Arch::Riscv64),
+            _ => Err(UnknownArch(arch.to_owned())),
+        }
+    }
+}
+
+#[derive(Clap, Debug)]
+#[clap(version = env!("CARGO_PKG_VERSION"), author = "Miguel Peláez <kernelfreak@outlook.com>")]
+pub struct Opts {
+    #[clap(subcommand)]
+    pub subcmd: SubCommands,
+}
+
+#[derive(Clap, Debug)]
+pub enum SubCommands {
+    /// Build a bootable disk image from the current kernel crate
+    Build(BuildOpts),
+
+    /// Build and run the current kernel crate in QEMU
+    Run(RunOpts),
+}
+
+#[derive(Clap, Debug)]
+pub struct BuildOpts {
+    /// Directory where the resulting images are written
+    #[clap(short, long, default_value = "out")]
+    pub out: PathBuf,
+
+    /// Create `out` if it doesn't already exist
+    #[clap(long)]
+    pub create_out: bool,
+
+    /// Target architecture to build the kernel for
+    #[clap(long, default_value = "x86_64")]
+    pub arch: Arch,
+
+    /// Cargo command used to build the kernel, e.g. "build --release"
+    #[clap(long, default_value = "build --release")]
+    pub build_cmd: String,
+
+    /// Manifest of `source=destination` pairs to embed into the ESP/FAT image
+    #[clap(long)]
+    pub bootfs: Option<PathBuf>,
+
+    /// Kernel command-line argument to embed as `cmdline.txt`, may be repeated
+    #[clap(long = "boot-arg", alias = "cmdline")]
+    pub boot_args: Vec<String>,
+
+    /// Skip producing a BIOS image
+    #[clap(long)]
+    pub disable_bios: bool,
+
+    /// Skip producing a UEFI image
+    #[clap(long)]
+    pub disable_uefi: bool,
+
+    /// Assemble a TFTP-servable PXE network boot directory instead of a disk image
+    #[clap(long)]
+    pub pxe: bool,
+
+    /// Produce a hash-tree-protected resource image alongside the disk image
+    #[clap(long)]
+    pub integrity: bool,
+
+    /// Compress the resource image payload before hashing it, requires --integrity
+    #[clap(long)]
+    pub compress: bool,
+}
+
+#[derive(Clap, Debug)]
+pub struct RunOpts {
+    /// Path to the compiled kernel binary
+    pub binary_path: PathBuf,
+
+    /// Directory where the resulting images are written
+    #[clap(short, long, default_value = "out")]
+    pub out: PathBuf,
+
+    /// Target architecture to boot the kernel as
+    #[clap(long, default_value = "x86_64")]
+    pub arch: Arch,
+
+    /// Boot the image over the emulated network via QEMU's built-in TFTP server
+    #[clap(long)]
+    pub pxe: bool,
+
+    /// Wait for a debugger on port 1234 instead of running immediately (`-s -S`)
+    #[clap(long)]
+    pub gdb: bool,
+
+    /// Redirect guest serial output, e.g. "stdio" to print it to the terminal
+    #[clap(long)]
+    pub serial: Option<String>,
+
+    /// Don't let the guest reboot itself on a triple fault
+    #[clap(long)]
+    pub no_reboot: bool,
+
+    /// isa-debug-exit code the guest reports on success, remapped to process exit code 0
+    #[clap(long, default_value = "5")]
+    pub success_exit_code: u8,
+
+    /// Extra arguments passed through to QEMU
+    #[clap(long, default_value = "")]
+    pub run_args: String,
+
+    /// Kill the virtual machine after this many seconds
+    #[clap(long)]
+    pub timeout: Option<u64>,
+}
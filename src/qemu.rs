@@ -0,0 +1,155 @@
+// Copyright (c) 2021 Miguel Peláez
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Typed QEMU orchestration for the `Run` subcommand. The `Command` is built
+//! up field-by-field from a [`VmConfig`] rather than string-splitting a raw
+//! argument blob, so debugger/serial/exit-code options compose cleanly with
+//! the user-supplied `--run-args`.
+
+use std::path::PathBuf;
+use std::process::{exit, Command};
+use std::time::Duration;
+
+use wait_timeout::ChildExt;
+
+use crate::opts::Arch;
+
+/// Options controlling how the guest VM is started and how its exit status is
+/// interpreted, threaded through from [`crate::opts::RunOpts`].
+pub struct VmConfig {
+    pub arch: Arch,
+    pub run_args: String,
+    pub timeout: Option<u64>,
+    pub gdb: bool,
+    pub serial: Option<String>,
+    pub no_reboot: bool,
+    pub success_exit_code: u8,
+}
+
+/// Firmware binary QEMU loads before handing control to the guest, used for
+/// the architectures that boot through UEFI/OpenSBI instead of a raw BIOS
+/// drive.
+fn firmware_for(arch: Arch) -> Option<&'static str> {
+    match arch {
+        Arch::X86_64 => None,
+        Arch::Aarch64 => Some("/usr/share/AAVMF/AAVMF_CODE.fd"),
+        Arch::Riscv64 => Some("/usr/share/qemu/opensbi-riscv64-generic-fw_dynamic.bin"),
+    }
+}
+
+fn apply_common_flags(cmd: &mut Command, config: &VmConfig) {
+    if config.gdb {
+        cmd.arg("-s").arg("-S");
+    }
+
+    if let Some(serial) = &config.serial {
+        cmd.arg("-serial").arg(serial);
+    }
+
+    if config.no_reboot {
+        cmd.arg("-no-reboot");
+    }
+
+    cmd.args(config.run_args.split(&[' ', '|'][..]).filter(|arg| !arg.is_empty()));
+}
+
+/// Boots `diskimage` as a disk/drive, the way each architecture expects.
+pub fn run(diskimage: PathBuf, config: VmConfig) {
+    let mut cmd = Command::new(config.arch.qemu_binary());
+
+    match config.arch {
+        Arch::X86_64 => {
+            cmd.arg("-drive")
+                .arg(format!("format=raw,file={}", diskimage.display()));
+        },
+        Arch::Aarch64 | Arch::Riscv64 => {
+            cmd.arg("-machine").arg("virt");
+
+            if let Some(firmware) = firmware_for(config.arch) {
+                cmd.arg("-bios").arg(firmware);
+            }
+
+            cmd.arg("-drive")
+                .arg(format!("if=virtio,format=raw,file={}", diskimage.display()));
+        },
+    }
+
+    apply_common_flags(&mut cmd, &config);
+
+    finish(cmd, &config);
+}
+
+/// Boots the kernel over QEMU's user-mode network stack instead of a disk
+/// image, serving `pxe_dir` as the guest's TFTP root.
+pub fn run_pxe(pxe_dir: PathBuf, config: VmConfig) {
+    let mut cmd = Command::new(config.arch.qemu_binary());
+
+    let nic = match config.arch {
+        Arch::X86_64 => "e1000",
+        Arch::Aarch64 | Arch::Riscv64 => {
+            cmd.arg("-machine").arg("virt");
+
+            if let Some(firmware) = firmware_for(config.arch) {
+                cmd.arg("-bios").arg(firmware);
+            }
+
+            "virtio-net-pci"
+        },
+    };
+
+    cmd.arg("-netdev").arg(format!(
+        "user,id=net0,tftp={},bootfile={}",
+        pxe_dir.display(),
+        config.arch.efi_boot_file_name()
+    ));
+    cmd.arg("-device").arg(format!("{},netdev=net0", nic));
+
+    apply_common_flags(&mut cmd, &config);
+
+    finish(cmd, &config);
+}
+
+fn finish(mut cmd: Command, config: &VmConfig) {
+    let mut child = cmd.spawn().expect("Failed to start virtual machine");
+
+    let status_code = if let Some(timeout) = config.timeout {
+        let timeout = Duration::from_secs(timeout);
+
+        match child
+            .wait_timeout(timeout)
+            .expect("Failed to wait for virtual machine")
+        {
+            Some(status) => status.code(),
+            None => {
+                // child hasn't exited yet
+                child.kill().unwrap();
+                child.wait().unwrap().code()
+            },
+        }
+    } else {
+        child.wait().expect("Failed to wait for virtual machine").code()
+    };
+
+    exit(
+        status_code
+            .map(|code| if code == config.success_exit_code as i32 { 0 } else { code })
+            .unwrap_or(1),
+    );
+}
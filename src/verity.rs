@@ -0,0 +1,124 @@
+// Copyright (c) 2021 Miguel Peláez
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Produces a "resource image": a disk image, optionally compressed, hashed
+//! with a Merkle tree and prefixed with a small header (magic, size,
+//! compression flag, root hash) so a loader can verify the payload before
+//! mounting it.
+//!
+//! This is a private, self-verifying format, not an on-disk-compatible
+//! `dm-verity` hash device: it has no salt, no superblock, and doesn't pad
+//! the final block to `BLOCK_SIZE`, so `veritysetup`/the kernel `dm-verity`
+//! target cannot read it.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::error::VerityError;
+
+const MAGIC: &[u8; 8] = b"ETHRIMG1";
+const BLOCK_SIZE: usize = 4096;
+const HASH_SIZE: usize = 32;
+
+/// Header prefixed to a produced resource image: enough for a loader to
+/// locate, decompress and hash-check the payload before mounting it.
+struct ResourceImageHeader {
+    image_size: u64,
+    compressed: bool,
+    root_hash: [u8; HASH_SIZE],
+}
+
+impl ResourceImageHeader {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MAGIC.len() + 8 + 1 + HASH_SIZE);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&self.image_size.to_le_bytes());
+        bytes.push(self.compressed as u8);
+        bytes.extend_from_slice(&self.root_hash);
+        bytes
+    }
+}
+
+/// Hashes `data` in `BLOCK_SIZE` blocks and folds the per-block hashes
+/// pairwise up to a single Merkle root.
+fn merkle_root(data: &[u8]) -> [u8; HASH_SIZE] {
+    let mut level: Vec<[u8; HASH_SIZE]> = data
+        .chunks(BLOCK_SIZE)
+        .map(|block| Sha256::digest(block).into())
+        .collect();
+
+    if level.is_empty() {
+        level.push(Sha256::digest([]).into());
+    }
+
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                let mut hasher = Sha256::new();
+                hasher.update(pair[0]);
+                hasher.update(pair.get(1).unwrap_or(&pair[0]));
+                hasher.finalize().into()
+            })
+            .collect();
+    }
+
+    level[0]
+}
+
+fn compress(data: &[u8]) -> Result<Vec<u8>, VerityError> {
+    let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+    encoder.write_all(data).map_err(VerityError::Compress)?;
+    encoder.finish().map_err(VerityError::Compress)
+}
+
+/// Reads `image_path`, optionally compresses it, computes a Merkle root over
+/// the payload, and writes `header + payload` to `out_path` as a single
+/// tamper-evident, optionally-compressed resource image.
+pub fn build_resource_image(image_path: &Path, out_path: &Path, compress_payload: bool) -> Result<(), VerityError> {
+    let mut raw = Vec::new();
+    File::open(image_path)
+        .map_err(VerityError::ReadImage)?
+        .read_to_end(&mut raw)
+        .map_err(VerityError::ReadImage)?;
+
+    let image_size = raw.len() as u64;
+    let payload = if compress_payload { compress(&raw)? } else { raw };
+
+    let header = ResourceImageHeader {
+        image_size,
+        compressed: compress_payload,
+        root_hash: merkle_root(&payload),
+    };
+
+    let mut out = File::create(out_path).map_err(VerityError::WriteResource)?;
+    out.write_all(&header.to_bytes()).map_err(VerityError::WriteResource)?;
+    out.write_all(&payload).map_err(VerityError::WriteResource)?;
+
+    Ok(())
+}
+
+/// Resource image path for a given disk image, alongside the original.
+pub fn resource_image_path(image_path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.verity", image_path.display()))
+}